@@ -1,23 +1,644 @@
 use tokenizers::Tokenizer;
 use tract_onnx::prelude::*;
 use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::CStr;
 use std::os::raw::c_char;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub struct EmbeddingsHandle {
+    provider: Box<dyn EmbeddingProvider>,
+    cache: Mutex<EmbeddingsCache>,
+}
+
+/// Abstraction over where embedding vectors come from, so the same FFI
+/// surface can drive a local ONNX model during development and a hosted
+/// embedding API in production. Implementors are responsible for returning
+/// L2-normalized vectors.
+trait EmbeddingProvider: Send + Sync {
+    fn dimension(&self) -> usize;
+
+    /// Embed a batch of texts, returning one normalized vector per text in
+    /// the same order as `texts`.
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String>;
+
+    /// Embed `text` as a sequence of overlapping chunks, each paired with
+    /// the `(start_byte, end_byte)` span it covers in `text`. Not every
+    /// provider can do this (it needs token-level offsets), so the default
+    /// implementation reports it as unsupported.
+    fn embed_chunks(
+        &self,
+        text: &str,
+        max_tokens: usize,
+        overlap: usize,
+    ) -> Result<Vec<(Vec<f32>, (usize, usize))>, String> {
+        let _ = (text, max_tokens, overlap);
+        Err("this embedding provider does not support chunked encoding".to_string())
+    }
+
+    /// Change the pooling strategy used for subsequent calls. A no-op for
+    /// providers where pooling doesn't apply (e.g. a remote API that
+    /// already returns pooled vectors).
+    fn set_pooling(&mut self, _strategy: PoolingStrategy) {}
+
+    /// Change the token budget used to size a single inference/request
+    /// call. A no-op for providers without such a budget.
+    fn set_max_batch_tokens(&mut self, _max_batch_tokens: usize) {}
+}
+
+/// Bounded content-hash cache of normalized embedding vectors, keyed by a
+/// hash of the input text's UTF-8 bytes. Content hash alone only identifies
+/// the text, not how it was encoded, so callers that change the active
+/// pooling strategy (`embeddings_set_pooling`) must clear the cache -
+/// `embeddings_set_pooling` does this itself. `capacity == 0` disables
+/// caching entirely.
+struct EmbeddingsCache {
+    capacity: usize,
+    map: HashMap<u64, Vec<f32>>,
+    order: VecDeque<u64>,
+    hits: u64,
+    misses: u64,
+}
+
+impl EmbeddingsCache {
+    fn new(capacity: usize) -> Self {
+        EmbeddingsCache {
+            capacity,
+            map: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: u64) -> Option<Vec<f32>> {
+        if self.capacity == 0 {
+            return None;
+        }
+        match self.map.get(&key) {
+            Some(v) => {
+                self.hits += 1;
+                if let Some(pos) = self.order.iter().position(|&k| k == key) {
+                    self.order.remove(pos);
+                }
+                self.order.push_back(key);
+                Some(v.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn put(&mut self, key: u64, value: Vec<f32>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.map.contains_key(&key) {
+            if self.map.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.map.remove(&oldest);
+                }
+            }
+            self.order.push_back(key);
+        }
+        self.map.insert(key, value);
+    }
+
+    /// Drop all cached entries (but keep the running hit/miss counters),
+    /// used when the encoding behind the cached vectors changes.
+    fn clear(&mut self) {
+        self.map.clear();
+        self.order.clear();
+    }
+}
+
+/// FNV-1a hash over raw bytes, used to key the embeddings cache by content.
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Default cap on total tokens that a single inference/request call is
+/// allowed to hold - the padded `batch_size * max_len` tensor size for the
+/// local provider, or an approximate summed character-based count for
+/// remote providers. Oversized batches are split into several sub-batches
+/// that each stay under this budget.
+const DEFAULT_MAX_BATCH_TOKENS: usize = 8192;
+
+/// Pooling strategy applied to the model's last hidden state to produce a
+/// single embedding vector.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolingStrategy {
+    /// Mean over unmasked (non-padding) positions. Correct default for
+    /// BGE/BERT-style models.
+    MeanMasked = 0,
+    /// Take the hidden state of the first token (`[CLS]`).
+    Cls = 1,
+    /// Elementwise max over unmasked positions.
+    MaxMasked = 2,
+}
+
+impl PoolingStrategy {
+    fn from_i32(v: i32) -> Self {
+        match v {
+            1 => PoolingStrategy::Cls,
+            2 => PoolingStrategy::MaxMasked,
+            _ => PoolingStrategy::MeanMasked,
+        }
+    }
+}
+
+/// Pool row `row` of a `(batch, seq_len, embedding_dim)` last-hidden-state
+/// view into a single embedding vector using `attention_mask` to ignore
+/// padding positions.
+fn pool_hidden_state(
+    tensor: &tract_ndarray::ArrayViewD<f32>,
+    row: usize,
+    attention_mask: &[u32],
+    seq_len: usize,
+    embedding_dim: usize,
+    strategy: PoolingStrategy,
+) -> Vec<f32> {
+    match strategy {
+        PoolingStrategy::Cls => {
+            let mut pooled = vec![0.0f32; embedding_dim];
+            for j in 0..embedding_dim {
+                pooled[j] = tensor[[row, 0, j]];
+            }
+            pooled
+        }
+        PoolingStrategy::MeanMasked => {
+            let mut pooled = vec![0.0f32; embedding_dim];
+            let mut mask_sum = 0.0f32;
+            for i in 0..seq_len {
+                let m = attention_mask[i] as f32;
+                if m == 0.0 {
+                    continue;
+                }
+                mask_sum += m;
+                for j in 0..embedding_dim {
+                    pooled[j] += tensor[[row, i, j]] * m;
+                }
+            }
+            let denom = if mask_sum > 1e-9 { mask_sum } else { 1.0 };
+            for val in pooled.iter_mut() {
+                *val /= denom;
+            }
+            pooled
+        }
+        PoolingStrategy::MaxMasked => {
+            let mut pooled = vec![f32::NEG_INFINITY; embedding_dim];
+            let mut saw_unmasked = false;
+            for i in 0..seq_len {
+                if attention_mask[i] == 0 {
+                    continue;
+                }
+                saw_unmasked = true;
+                for j in 0..embedding_dim {
+                    let v = tensor[[row, i, j]];
+                    if v > pooled[j] {
+                        pooled[j] = v;
+                    }
+                }
+            }
+            if !saw_unmasked {
+                pooled.iter_mut().for_each(|v| *v = 0.0);
+            }
+            pooled
+        }
+    }
+}
+
+/// Normalize a vector to unit length (L2 normalization)
+fn normalize_vector(vec: &mut [f32]) {
+    let norm: f32 = vec.iter()
+        .map(|x| x * x)
+        .sum::<f32>()
+        .sqrt();
+
+    if norm > 1e-12 {  // Avoid division by zero
+        for val in vec.iter_mut() {
+            *val /= norm;
+        }
+    }
+}
+
+/// A single tokenized (and truncated) text, tagged with its position in the
+/// original request so results can be reassembled in order after batching.
+struct TokenizedText {
+    idx: usize,
+    input_ids: Vec<u32>,
+    attention_mask: Vec<u32>,
+    token_type_ids: Vec<u32>,
+}
+
+/// Split tokenized texts into sub-batches whose *padded* size
+/// (`batch_size * max_len`, the shape of the tensor actually run through
+/// the model) stays under `max_batch_tokens`. Items are sorted shortest
+/// first so `max_len` grows monotonically as a sub-batch fills, which keeps
+/// a batch's few longest rows from dragging every other row's padding up
+/// with them; without sorting, one long text mixed with many short ones
+/// would pad all of them to the long text's length. Always keeps at least
+/// one item per sub-batch, even if that item alone exceeds the budget, so a
+/// single very long text can't get stuck.
+fn make_sub_batches(mut items: Vec<TokenizedText>, max_batch_tokens: usize) -> Vec<Vec<TokenizedText>> {
+    items.sort_by_key(|t| t.input_ids.len());
+
+    let mut sub_batches = Vec::new();
+    let mut current = Vec::new();
+    let mut current_max_len = 0usize;
+
+    for item in items {
+        let item_tokens = item.input_ids.len();
+        let padded_size = (current.len() + 1) * current_max_len.max(item_tokens);
+        if !current.is_empty() && padded_size > max_batch_tokens {
+            sub_batches.push(std::mem::take(&mut current));
+            current_max_len = 0;
+        }
+        current_max_len = current_max_len.max(item_tokens);
+        current.push(item);
+    }
+    if !current.is_empty() {
+        sub_batches.push(current);
+    }
+
+    sub_batches
+}
+
+/// A local tract ONNX model plus its tokenizer. Wraps the batched inference
+/// and token-aware chunking logic behind `EmbeddingProvider`.
+struct LocalOnnx {
     tokenizer: Tokenizer,
     model: Arc<SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>>,
     pool: rayon::ThreadPool,
     embedding_dim: usize,
+    pooling: Mutex<PoolingStrategy>,
+    max_batch_tokens: Mutex<usize>,
 }
 
-/// Initialize embeddings model
-/// Returns NULL on error
-#[no_mangle]
-pub extern "C" fn embeddings_init(
+const MAX_SEQ_LENGTH: usize = 512;
+
+impl EmbeddingProvider for LocalOnnx {
+    fn dimension(&self) -> usize {
+        self.embedding_dim
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let pooling = *self.pooling.lock().unwrap();
+        let max_batch_tokens = *self.max_batch_tokens.lock().unwrap();
+        let pad_id = self.tokenizer.token_to_id("[PAD]").unwrap_or(0);
+
+        let start = std::time::Instant::now();
+
+        // `Cls` pooling reads row 0 of the sequence expecting it to be
+        // `[CLS]`, so it needs the tokenizer to actually add special tokens;
+        // the other strategies pool over the whole (masked) sequence and
+        // don't care.
+        let add_special_tokens = pooling == PoolingStrategy::Cls;
+
+        let mut tokenized = Vec::with_capacity(texts.len());
+        for (idx, text) in texts.iter().enumerate() {
+            let encoding = self.tokenizer.encode(text.as_str(), add_special_tokens)
+                .map_err(|e| format!("Text {} tokenization failed: {}", idx, e))?;
+            let len = encoding.get_ids().len().min(MAX_SEQ_LENGTH);
+            tokenized.push(TokenizedText {
+                idx,
+                input_ids: encoding.get_ids()[..len].to_vec(),
+                attention_mask: encoding.get_attention_mask()[..len].to_vec(),
+                token_type_ids: encoding.get_type_ids()[..len].to_vec(),
+            });
+        }
+
+        let sub_batches = make_sub_batches(tokenized, max_batch_tokens);
+        eprintln!("[RAYON] Split {} texts into {} sub-batch(es) (max_batch_tokens={})",
+            texts.len(), sub_batches.len(), max_batch_tokens);
+
+        // Run sub-batches concurrently on the provider's rayon pool; within
+        // a sub-batch all rows are padded to a common max length and go
+        // through a single model.run call.
+        let results: Vec<Result<Vec<(usize, Vec<f32>)>, String>> = self.pool.install(|| {
+            sub_batches.into_par_iter().map(|batch| {
+                let batch_start = std::time::Instant::now();
+                let batch_size = batch.len();
+                let max_len = batch.iter().map(|t| t.input_ids.len()).max().unwrap_or(0);
+
+                let mut input_ids_flat = Vec::with_capacity(batch_size * max_len);
+                let mut attention_mask_flat = Vec::with_capacity(batch_size * max_len);
+                let mut token_type_ids_flat = Vec::with_capacity(batch_size * max_len);
+
+                for t in &batch {
+                    let pad_len = max_len - t.input_ids.len();
+                    input_ids_flat.extend(t.input_ids.iter().map(|&x| x as i64));
+                    input_ids_flat.extend(std::iter::repeat(pad_id as i64).take(pad_len));
+
+                    attention_mask_flat.extend(t.attention_mask.iter().map(|&x| x as i64));
+                    attention_mask_flat.extend(std::iter::repeat(0i64).take(pad_len));
+
+                    token_type_ids_flat.extend(t.token_type_ids.iter().map(|&x| x as i64));
+                    token_type_ids_flat.extend(std::iter::repeat(0i64).take(pad_len));
+                }
+
+                let input_ids_array = tract_ndarray::Array2::from_shape_vec((batch_size, max_len), input_ids_flat).unwrap();
+                let attention_mask_array = tract_ndarray::Array2::from_shape_vec((batch_size, max_len), attention_mask_flat).unwrap();
+                let token_type_ids_array = tract_ndarray::Array2::from_shape_vec((batch_size, max_len), token_type_ids_flat).unwrap();
+
+                let outputs = self.model.run(tvec!(
+                    Tensor::from(input_ids_array).into(),
+                    Tensor::from(attention_mask_array).into(),
+                    Tensor::from(token_type_ids_array).into(),
+                )).map_err(|e| format!("Sub-batch of {} inference failed: {}", batch_size, e))?;
+
+                let tensor = outputs[0].to_array_view::<f32>()
+                    .map_err(|e| format!("Sub-batch of {} failed to extract embeddings: {}", batch_size, e))?;
+
+                let embedding_dim = tensor.shape()[2];
+
+                let mut row_embeddings = Vec::with_capacity(batch_size);
+                for (row, t) in batch.iter().enumerate() {
+                    let mut pooled = pool_hidden_state(
+                        &tensor,
+                        row,
+                        &t.attention_mask,
+                        t.input_ids.len(),
+                        embedding_dim,
+                        pooling,
+                    );
+                    normalize_vector(&mut pooled);
+                    row_embeddings.push((t.idx, pooled));
+                }
+
+                eprintln!("[RAYON] Sub-batch of {} texts (max_len={}): {}ms",
+                    batch_size, max_len, batch_start.elapsed().as_millis());
+
+                Ok(row_embeddings)
+            }).collect()
+        });
+
+        let elapsed_ms = start.elapsed().as_millis();
+        eprintln!("[RAYON] Processed {} texts in {}ms ({:.1} texts/sec)",
+            texts.len(), elapsed_ms, texts.len() as f64 / (elapsed_ms as f64 / 1000.0));
+
+        let mut ordered: Vec<Option<Vec<f32>>> = (0..texts.len()).map(|_| None).collect();
+        for result in results.into_iter() {
+            let rows = result?;
+            for (idx, embedding) in rows {
+                ordered[idx] = Some(embedding);
+            }
+        }
+
+        ordered.into_iter().enumerate()
+            .map(|(idx, e)| e.ok_or_else(|| format!("missing embedding for text {}", idx)))
+            .collect()
+    }
+
+    fn embed_chunks(
+        &self,
+        text: &str,
+        max_tokens: usize,
+        overlap: usize,
+    ) -> Result<Vec<(Vec<f32>, (usize, usize))>, String> {
+        if max_tokens == 0 || overlap >= max_tokens {
+            return Err("max_tokens must be > 0 and > overlap".to_string());
+        }
+
+        let pooling = *self.pooling.lock().unwrap();
+        if pooling == PoolingStrategy::Cls {
+            return Err("Cls pooling is not supported for chunked encoding: only the first \
+                window has [CLS] at its start, and special tokens would corrupt the source \
+                byte ranges this feature exists to produce".to_string());
+        }
+
+        // Unlike `embed_batch`, chunking never adds special tokens: `offsets`
+        // here drives the source byte ranges returned to the caller, and a
+        // trailing `[SEP]` reports offset `(0, 0)`, which would corrupt the
+        // last chunk's range.
+        let encoding = self.tokenizer.encode(text, false)
+            .map_err(|e| format!("Chunked tokenization failed: {}", e))?;
+
+        let input_ids = encoding.get_ids();
+        let attention_mask = encoding.get_attention_mask();
+        let token_type_ids = encoding.get_type_ids();
+        let offsets = encoding.get_offsets();
+        let total_len = input_ids.len();
+
+        if total_len == 0 {
+            return Err("empty token stream".to_string());
+        }
+
+        let stride = max_tokens - overlap;
+        let mut chunks = Vec::new();
+        let mut start = 0usize;
+
+        loop {
+            let end = (start + max_tokens).min(total_len);
+
+            let input_ids_array = tract_ndarray::Array2::from_shape_vec(
+                (1, end - start),
+                input_ids[start..end].iter().map(|&x| x as i64).collect(),
+            ).unwrap();
+            let attention_mask_array = tract_ndarray::Array2::from_shape_vec(
+                (1, end - start),
+                attention_mask[start..end].iter().map(|&x| x as i64).collect(),
+            ).unwrap();
+            let token_type_ids_array = tract_ndarray::Array2::from_shape_vec(
+                (1, end - start),
+                token_type_ids[start..end].iter().map(|&x| x as i64).collect(),
+            ).unwrap();
+
+            let outputs = self.model.run(tvec!(
+                Tensor::from(input_ids_array).into(),
+                Tensor::from(attention_mask_array).into(),
+                Tensor::from(token_type_ids_array).into(),
+            )).map_err(|e| format!("Chunk [{}, {}) inference failed: {}", start, end, e))?;
+
+            let tensor = outputs[0].to_array_view::<f32>()
+                .map_err(|e| format!("Chunk [{}, {}) failed to extract embeddings: {}", start, end, e))?;
+            let embedding_dim = tensor.shape()[2];
+
+            let mut pooled = pool_hidden_state(&tensor, 0, &attention_mask[start..end], end - start, embedding_dim, pooling);
+            normalize_vector(&mut pooled);
+
+            chunks.push((pooled, (offsets[start].0, offsets[end - 1].1)));
+
+            if end == total_len {
+                break;
+            }
+            start += stride;
+        }
+
+        eprintln!("[RAYON] Chunked text of {} tokens into {} chunk(s) (max_tokens={}, overlap={})",
+            total_len, chunks.len(), max_tokens, overlap);
+
+        Ok(chunks)
+    }
+
+    fn set_pooling(&mut self, strategy: PoolingStrategy) {
+        *self.pooling.lock().unwrap() = strategy;
+    }
+
+    fn set_max_batch_tokens(&mut self, max_batch_tokens: usize) {
+        *self.max_batch_tokens.lock().unwrap() = max_batch_tokens;
+    }
+}
+
+/// JSON response shape expected from a remote embedding endpoint, following
+/// the common OpenAI-compatible `{"data": [{"embedding": [...]}]}` format.
+#[derive(Deserialize)]
+struct RemoteEmbedResponse {
+    data: Vec<RemoteEmbedDatum>,
+}
+
+#[derive(Deserialize)]
+struct RemoteEmbedDatum {
+    embedding: Vec<f32>,
+}
+
+/// A hosted embedding API, batched into token-budgeted HTTP requests with
+/// exponential backoff on HTTP 429. `dimension` is supplied by the caller at
+/// construction time (there's no local model to inspect for it) and is
+/// overwritten with the length of the first vector of each successful
+/// response.
+struct RemoteHttp {
+    endpoint: String,
+    model_name: String,
+    api_key: String,
+    dimension: AtomicUsize,
+    max_batch_tokens: AtomicUsize,
+    max_retries: u32,
+    max_backoff_ms: u64,
+}
+
+/// Very rough token estimate for packing remote requests, used only to size
+/// batches (the remote service does its own real tokenization).
+fn approx_token_count(text: &str) -> usize {
+    (text.len() / 4).max(1)
+}
+
+impl RemoteHttp {
+    /// Group text indices into sub-batches whose approximate token count
+    /// stays under the configured budget, mirroring `make_sub_batches`.
+    fn pack_sub_batches(&self, texts: &[String]) -> Vec<Vec<usize>> {
+        let budget = self.max_batch_tokens.load(Ordering::Relaxed);
+        let mut sub_batches = Vec::new();
+        let mut current = Vec::new();
+        let mut current_tokens = 0usize;
+
+        for (idx, text) in texts.iter().enumerate() {
+            let tokens = approx_token_count(text);
+            if !current.is_empty() && current_tokens + tokens > budget {
+                sub_batches.push(std::mem::take(&mut current));
+                current_tokens = 0;
+            }
+            current_tokens += tokens;
+            current.push(idx);
+        }
+        if !current.is_empty() {
+            sub_batches.push(current);
+        }
+
+        sub_batches
+    }
+
+    /// Send one HTTP request for `texts`, retrying on HTTP 429 with
+    /// exponential backoff (honoring `Retry-After` when present) up to
+    /// `max_retries`, capped at `max_backoff_ms`.
+    fn embed_sub_batch(&self, texts: &[&String]) -> Result<Vec<Vec<f32>>, String> {
+        let body = serde_json::json!({
+            "model": self.model_name,
+            "input": texts,
+        });
+
+        let mut attempt = 0u32;
+        let mut backoff_ms = 250u64;
+
+        loop {
+            let response = ureq::post(&self.endpoint)
+                .set("Authorization", &format!("Bearer {}", self.api_key))
+                .set("Content-Type", "application/json")
+                .send_json(body.clone());
+
+            match response {
+                Ok(resp) => {
+                    let parsed: RemoteEmbedResponse = resp.into_json()
+                        .map_err(|e| format!("remote embeddings: failed to parse response: {}", e))?;
+                    let vectors: Vec<Vec<f32>> = parsed.data.into_iter().map(|d| d.embedding).collect();
+                    if let Some(first) = vectors.first() {
+                        self.dimension.store(first.len(), Ordering::Relaxed);
+                    }
+                    return Ok(vectors);
+                }
+                Err(ureq::Error::Status(429, resp)) => {
+                    if attempt >= self.max_retries {
+                        return Err(format!("remote embeddings: rate limited after {} retries", attempt));
+                    }
+                    let wait_ms = resp.header("Retry-After")
+                        .and_then(|s| s.parse::<u64>().ok())
+                        .map(|secs| secs * 1000)
+                        .unwrap_or(backoff_ms)
+                        .min(self.max_backoff_ms);
+                    eprintln!("[RAYON] Remote embeddings rate limited, backing off {}ms (attempt {})", wait_ms, attempt + 1);
+                    std::thread::sleep(std::time::Duration::from_millis(wait_ms));
+                    backoff_ms = (backoff_ms * 2).min(self.max_backoff_ms);
+                    attempt += 1;
+                }
+                Err(e) => return Err(format!("remote embeddings request failed: {}", e)),
+            }
+        }
+    }
+}
+
+impl EmbeddingProvider for RemoteHttp {
+    fn dimension(&self) -> usize {
+        self.dimension.load(Ordering::Relaxed)
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let sub_batches = self.pack_sub_batches(texts);
+        let mut results: Vec<Vec<f32>> = vec![Vec::new(); texts.len()];
+
+        for indices in sub_batches {
+            let refs: Vec<&String> = indices.iter().map(|&i| &texts[i]).collect();
+            let mut vectors = self.embed_sub_batch(&refs)?;
+            if vectors.len() != indices.len() {
+                return Err(format!(
+                    "remote embeddings: expected {} vectors, got {}",
+                    indices.len(), vectors.len()
+                ));
+            }
+            for (pos, &idx) in indices.iter().enumerate() {
+                let mut v = std::mem::take(&mut vectors[pos]);
+                normalize_vector(&mut v);
+                results[idx] = v;
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn set_max_batch_tokens(&mut self, max_batch_tokens: usize) {
+        self.max_batch_tokens.store(max_batch_tokens, Ordering::Relaxed);
+    }
+}
+
+/// Shared implementation behind `embeddings_init` and
+/// `embeddings_init_with_cache`. `cache_capacity` of 0 disables the cache.
+fn init_handle(
     model_path: *const c_char,
     tokenizer_path: *const c_char,
+    pooling_strategy: i32,
+    cache_capacity: usize,
 ) -> *mut EmbeddingsHandle {
     if model_path.is_null() || tokenizer_path.is_null() {
         return std::ptr::null_mut();
@@ -73,28 +694,154 @@ pub extern "C" fn embeddings_init(
 
     eprintln!("[RAYON] Model loaded successfully, using {} threads for parallel processing", num_threads);
 
-    let handle = Box::new(EmbeddingsHandle {
+    let provider = LocalOnnx {
         tokenizer,
         model: Arc::new(model),
         pool,
         embedding_dim,
+        pooling: Mutex::new(PoolingStrategy::from_i32(pooling_strategy)),
+        max_batch_tokens: Mutex::new(DEFAULT_MAX_BATCH_TOKENS),
+    };
+
+    let handle = Box::new(EmbeddingsHandle {
+        provider: Box::new(provider),
+        cache: Mutex::new(EmbeddingsCache::new(cache_capacity)),
     });
 
     Box::into_raw(handle)
 }
 
-/// Normalize a vector to unit length (L2 normalization)
-fn normalize_vector(vec: &mut [f32]) {
-    let norm: f32 = vec.iter()
-        .map(|x| x * x)
-        .sum::<f32>()
-        .sqrt();
+/// Initialize embeddings model
+/// Returns NULL on error
+#[no_mangle]
+pub extern "C" fn embeddings_init(
+    model_path: *const c_char,
+    tokenizer_path: *const c_char,
+    pooling_strategy: i32,
+) -> *mut EmbeddingsHandle {
+    init_handle(model_path, tokenizer_path, pooling_strategy, 0)
+}
 
-    if norm > 1e-12 {  // Avoid division by zero
-        for val in vec.iter_mut() {
-            *val /= norm;
-        }
+/// Initialize embeddings model with a bounded content-hash cache of
+/// `cache_capacity` entries. `embeddings_encode`/`embeddings_encode_batch`
+/// check the cache before tokenizing and populate it afterward, keyed by a
+/// hash of the input text. Pass `cache_capacity == 0` to disable caching
+/// (equivalent to `embeddings_init`). Returns NULL on error.
+#[no_mangle]
+pub extern "C" fn embeddings_init_with_cache(
+    model_path: *const c_char,
+    tokenizer_path: *const c_char,
+    pooling_strategy: i32,
+    cache_capacity: usize,
+) -> *mut EmbeddingsHandle {
+    init_handle(model_path, tokenizer_path, pooling_strategy, cache_capacity)
+}
+
+/// Initialize embeddings backed by a remote HTTP embedding API instead of a
+/// local ONNX model. `endpoint` is the full URL of an OpenAI-compatible
+/// embeddings endpoint; requests are authenticated with
+/// `Authorization: Bearer <api_key>` and batched under a token budget (see
+/// `embeddings_set_max_batch_tokens`). HTTP 429 responses are retried with
+/// exponential backoff (honoring `Retry-After` when present) up to a fixed
+/// retry cap. `dimension` must be the provider's embedding vector length
+/// (there's no local model here to infer it from) and is what
+/// `embeddings_get_dimension` reports immediately, before any request has
+/// been made. Returns NULL on a null/invalid argument, including
+/// `dimension == 0`.
+#[no_mangle]
+pub extern "C" fn embeddings_init_remote(
+    endpoint: *const c_char,
+    model_name: *const c_char,
+    api_key: *const c_char,
+    dimension: usize,
+) -> *mut EmbeddingsHandle {
+    if endpoint.is_null() || model_name.is_null() || api_key.is_null() || dimension == 0 {
+        return std::ptr::null_mut();
     }
+
+    let endpoint = match unsafe { CStr::from_ptr(endpoint) }.to_str() {
+        Ok(s) => s.to_string(),
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let model_name = match unsafe { CStr::from_ptr(model_name) }.to_str() {
+        Ok(s) => s.to_string(),
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let api_key = match unsafe { CStr::from_ptr(api_key) }.to_str() {
+        Ok(s) => s.to_string(),
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let provider = RemoteHttp {
+        endpoint,
+        model_name,
+        api_key,
+        dimension: AtomicUsize::new(dimension),
+        max_batch_tokens: AtomicUsize::new(DEFAULT_MAX_BATCH_TOKENS),
+        max_retries: 5,
+        max_backoff_ms: 30_000,
+    };
+
+    let handle = Box::new(EmbeddingsHandle {
+        provider: Box::new(provider),
+        cache: Mutex::new(EmbeddingsCache::new(0)),
+    });
+
+    Box::into_raw(handle)
+}
+
+/// Report cache hit/miss counts accumulated since the handle was created.
+/// Returns false on a null handle or null output pointers.
+#[no_mangle]
+pub extern "C" fn embeddings_cache_stats(
+    handle: *const EmbeddingsHandle,
+    hits: *mut u64,
+    misses: *mut u64,
+) -> bool {
+    if handle.is_null() || hits.is_null() || misses.is_null() {
+        return false;
+    }
+    let handle = unsafe { &*handle };
+    let cache = handle.cache.lock().unwrap();
+    unsafe {
+        *hits = cache.hits;
+        *misses = cache.misses;
+    }
+    true
+}
+
+/// Set the max-batch-token budget used by `embeddings_encode_batch` to split
+/// oversized batches into sub-batches. Returns false on a null handle or a
+/// zero budget.
+#[no_mangle]
+pub extern "C" fn embeddings_set_max_batch_tokens(
+    handle: *mut EmbeddingsHandle,
+    max_batch_tokens: usize,
+) -> bool {
+    if handle.is_null() || max_batch_tokens == 0 {
+        return false;
+    }
+    let handle = unsafe { &mut *handle };
+    handle.provider.set_max_batch_tokens(max_batch_tokens);
+    true
+}
+
+/// Change the pooling strategy used by subsequent `embeddings_encode` /
+/// `embeddings_encode_batch` calls on this handle. Also clears the handle's
+/// cache, since its entries were produced under the old pooling strategy.
+/// `strategy` follows `PoolingStrategy` (0=MeanMasked, 1=Cls, 2=MaxMasked).
+#[no_mangle]
+pub extern "C" fn embeddings_set_pooling(
+    handle: *mut EmbeddingsHandle,
+    strategy: i32,
+) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+    let handle = unsafe { &mut *handle };
+    handle.provider.set_pooling(PoolingStrategy::from_i32(strategy));
+    handle.cache.lock().unwrap().clear();
+    true
 }
 
 /// Encode text to normalized embeddings
@@ -117,84 +864,32 @@ pub extern "C" fn embeddings_encode(
         Err(_) => return false,
     };
 
-    // Tokenize with truncation (BGE models use 512 max sequence length)
-    let encoding = match handle.tokenizer.encode(text, false) {
-        Ok(e) => e,
-        Err(e) => {
-            eprintln!("[RAYON] Tokenization failed: {}", e);
-            return false;
+    let cache_key = fnv1a_hash(text.as_bytes());
+    if let Some(cached) = handle.cache.lock().unwrap().get(cache_key) {
+        let len = cached.len();
+        let mut boxed = cached.into_boxed_slice();
+        let ptr = boxed.as_mut_ptr();
+        std::mem::forget(boxed);
+        unsafe {
+            *embeddings_out = ptr;
+            *len_out = len;
         }
-    };
+        return true;
+    }
 
-    const MAX_SEQ_LENGTH: usize = 512;
-    let mut input_ids = encoding.get_ids();
-    let mut attention_mask = encoding.get_attention_mask();
-    let mut token_type_ids = encoding.get_type_ids();
-
-    // Truncate if needed
-    if input_ids.len() > MAX_SEQ_LENGTH {
-        eprintln!("[RAYON] Truncating sequence from {} to {} tokens", input_ids.len(), MAX_SEQ_LENGTH);
-        input_ids = &input_ids[..MAX_SEQ_LENGTH];
-        attention_mask = &attention_mask[..MAX_SEQ_LENGTH];
-        token_type_ids = &token_type_ids[..MAX_SEQ_LENGTH];
-    }
-
-    // Prepare tract inputs (i64 tensors)
-    let input_ids_array = tract_ndarray::Array2::from_shape_vec(
-        (1, input_ids.len()),
-        input_ids.iter().map(|&x| x as i64).collect(),
-    ).unwrap();
-
-    let attention_mask_array = tract_ndarray::Array2::from_shape_vec(
-        (1, attention_mask.len()),
-        attention_mask.iter().map(|&x| x as i64).collect(),
-    ).unwrap();
-
-    let token_type_ids_array = tract_ndarray::Array2::from_shape_vec(
-        (1, token_type_ids.len()),
-        token_type_ids.iter().map(|&x| x as i64).collect(),
-    ).unwrap();
-
-    // Run inference (tract sessions are thread-safe)
-    let outputs = match handle.model.run(tvec!(
-        Tensor::from(input_ids_array).into(),
-        Tensor::from(attention_mask_array).into(),
-        Tensor::from(token_type_ids_array).into(),
-    )) {
-        Ok(o) => o,
-        Err(e) => {
-            eprintln!("[RAYON] ONNX inference failed: {}", e);
+    let embeddings = match handle.provider.embed_batch(&[text.to_string()]) {
+        Ok(mut vectors) if !vectors.is_empty() => vectors.remove(0),
+        Ok(_) => {
+            eprintln!("[RAYON] Encoding failed: provider returned no embedding");
             return false;
         }
-    };
-
-    // Extract embeddings
-    let mut embeddings = match outputs[0].to_array_view::<f32>() {
-        Ok(tensor) => {
-            let shape = tensor.shape();
-            let seq_len = shape[1];
-            let embedding_dim = shape[2];
-
-            // Mean pooling over sequence dimension
-            let mut pooled = vec![0.0f32; embedding_dim];
-            for i in 0..seq_len {
-                for j in 0..embedding_dim {
-                    pooled[j] += tensor[[0, i, j]];
-                }
-            }
-            for val in pooled.iter_mut() {
-                *val /= seq_len as f32;
-            }
-            pooled
-        }
         Err(e) => {
-            eprintln!("[RAYON] Failed to extract embeddings: {}", e);
+            eprintln!("[RAYON] {}", e);
             return false;
         }
     };
 
-    // Always normalize for BGE models
-    normalize_vector(&mut embeddings);
+    handle.cache.lock().unwrap().put(cache_key, embeddings.clone());
 
     // Allocate output
     let len = embeddings.len();
@@ -210,7 +905,12 @@ pub extern "C" fn embeddings_encode(
     true
 }
 
-/// Encode batch of texts to normalized embeddings (parallel with rayon)
+/// Encode batch of texts to normalized embeddings.
+///
+/// Cache hits are served directly; cache misses are handed to the handle's
+/// `EmbeddingProvider` (a local ONNX model batched with padding, or a
+/// remote HTTP provider batched under a token budget) as a single
+/// `embed_batch` call.
 #[no_mangle]
 pub extern "C" fn embeddings_encode_batch(
     handle: *mut EmbeddingsHandle,
@@ -243,94 +943,47 @@ pub extern "C" fn embeddings_encode_batch(
         }
     }
 
-    let start = std::time::Instant::now();
-
-    // Process texts in parallel using rayon
-    let results: Vec<Result<Vec<f32>, String>> = handle.pool.install(|| {
-        text_strings.par_iter().enumerate().map(|(idx, text)| {
-            let iter_start = std::time::Instant::now();
-
-            let tok_start = std::time::Instant::now();
-            let encoding = handle.tokenizer.encode(text.as_str(), false)
-                .map_err(|e| format!("Text {} tokenization failed: {}", idx, e))?;
-            let tok_ms = tok_start.elapsed().as_millis();
-
-            let tensor_start = std::time::Instant::now();
-
-            const MAX_SEQ_LENGTH: usize = 512;
-            let input_ids = encoding.get_ids();
-            let attention_mask = encoding.get_attention_mask();
-            let token_type_ids = encoding.get_type_ids();
-
-            let seq_len = input_ids.len().min(MAX_SEQ_LENGTH);
-
-            let input_ids_array = tract_ndarray::Array2::from_shape_vec(
-                (1, seq_len),
-                input_ids[..seq_len].iter().map(|&x| x as i64).collect(),
-            ).unwrap();
-
-            let attention_mask_array = tract_ndarray::Array2::from_shape_vec(
-                (1, seq_len),
-                attention_mask[..seq_len].iter().map(|&x| x as i64).collect(),
-            ).unwrap();
-
-            let token_type_ids_array = tract_ndarray::Array2::from_shape_vec(
-                (1, seq_len),
-                token_type_ids[..seq_len].iter().map(|&x| x as i64).collect(),
-            ).unwrap();
-
-            let tensor_ms = tensor_start.elapsed().as_millis();
-
-            // Run inference (tract sessions are thread-safe)
-            let infer_start = std::time::Instant::now();
-            let outputs = handle.model.run(tvec!(
-                Tensor::from(input_ids_array).into(),
-                Tensor::from(attention_mask_array).into(),
-                Tensor::from(token_type_ids_array).into(),
-            )).map_err(|e| format!("Text {} inference failed: {}", idx, e))?;
-            let infer_ms = infer_start.elapsed().as_millis();
-
-            // Extract and pool embeddings
-            let tensor = outputs[0].to_array_view::<f32>()
-                .map_err(|e| format!("Text {} failed to extract embeddings: {}", idx, e))?;
-
-            let shape = tensor.shape();
-            let embedding_dim = shape[2];
-
-            let mut pooled = vec![0.0f32; embedding_dim];
-            for i in 0..seq_len {
-                for j in 0..embedding_dim {
-                    pooled[j] += tensor[[0, i, j]];
-                }
-            }
-            for val in pooled.iter_mut() {
-                *val /= seq_len as f32;
+    // Check the cache before handing anything to the provider; only texts
+    // that miss need to go through tokenization/inference at all.
+    let cache_keys: Vec<u64> = text_strings.iter().map(|t| fnv1a_hash(t.as_bytes())).collect();
+    let mut ordered: Vec<Option<Vec<f32>>> = (0..num_texts).map(|_| None).collect();
+    let mut miss_indices = Vec::new();
+    {
+        let mut cache = handle.cache.lock().unwrap();
+        for (idx, &key) in cache_keys.iter().enumerate() {
+            match cache.get(key) {
+                Some(v) => ordered[idx] = Some(v),
+                None => miss_indices.push(idx),
             }
+        }
+    }
 
-            // Normalize
-            normalize_vector(&mut pooled);
-
-            let total_ms = iter_start.elapsed().as_millis();
-            eprintln!("[RAYON] Text {}: tok={}ms tensor={}ms infer={}ms total={}ms",
-                idx, tok_ms, tensor_ms, infer_ms, total_ms);
-
-            Ok(pooled)
-        }).collect()
-    });
-
-    let elapsed_ms = start.elapsed().as_millis();
-    eprintln!("[RAYON] Processed {} texts in {}ms ({:.1} texts/sec)",
-        num_texts, elapsed_ms, num_texts as f64 / (elapsed_ms as f64 / 1000.0));
-
-    // Check for errors
-    let mut all_embeddings = Vec::with_capacity(num_texts * handle.embedding_dim);
-    for (_, result) in results.into_iter().enumerate() {
-        match result {
-            Ok(embedding) => all_embeddings.extend(embedding),
+    if !miss_indices.is_empty() {
+        let miss_texts: Vec<String> = miss_indices.iter().map(|&i| text_strings[i].clone()).collect();
+        let embeddings = match handle.provider.embed_batch(&miss_texts) {
+            Ok(e) => e,
             Err(e) => {
                 eprintln!("[RAYON] {}", e);
                 return false;
             }
+        };
+
+        let mut cache = handle.cache.lock().unwrap();
+        for (&idx, embedding) in miss_indices.iter().zip(embeddings.into_iter()) {
+            cache.put(cache_keys[idx], embedding.clone());
+            ordered[idx] = Some(embedding);
+        }
+    }
+
+    let embedding_dim = handle.provider.dimension();
+    let mut all_embeddings = Vec::with_capacity(num_texts * embedding_dim);
+    for embedding in ordered.into_iter() {
+        match embedding {
+            Some(e) => all_embeddings.extend(e),
+            None => {
+                eprintln!("[RAYON] Batch encoding failed: missing embedding for a text");
+                return false;
+            }
         }
     }
 
@@ -352,6 +1005,78 @@ pub extern "C" fn embeddings_encode_batch(
     true
 }
 
+/// Encode a long text as a sequence of overlapping chunk embeddings.
+///
+/// Slides a window of `max_tokens` tokens (advancing by `max_tokens -
+/// overlap` each step) across the text, embedding each window through the
+/// handle's `EmbeddingProvider`. This avoids the silent 512-token
+/// truncation that `embeddings_encode` applies, so the tail of a long
+/// document still gets embedded. Only providers with token-level offsets
+/// (the local ONNX provider) support this; a remote provider returns false.
+/// `Cls` pooling is also unsupported here (returns false) since `[CLS]`
+/// only exists at the start of the first window.
+/// `out_embeddings` receives `out_count` vectors of
+/// `embeddings_get_dimension` floats each, concatenated. `out_ranges`
+/// receives `out_count` `(start_byte, end_byte)` pairs (flattened, so
+/// `2 * out_count` usize values), giving the byte offset span each chunk
+/// covers in the original `text`. Free the two outputs with
+/// `embeddings_free_result` and `embeddings_free_ranges` respectively.
+#[no_mangle]
+pub extern "C" fn embeddings_encode_chunked(
+    handle: *mut EmbeddingsHandle,
+    text: *const c_char,
+    max_tokens: usize,
+    overlap: usize,
+    out_embeddings: *mut *mut f32,
+    out_ranges: *mut *mut usize,
+    out_count: *mut usize,
+) -> bool {
+    if handle.is_null() || text.is_null() || out_embeddings.is_null()
+        || out_ranges.is_null() || out_count.is_null() || max_tokens == 0 || overlap >= max_tokens {
+        return false;
+    }
+
+    let handle = unsafe { &mut *handle };
+    let c_str = unsafe { CStr::from_ptr(text) };
+    let text = match c_str.to_str() {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    let chunks = match handle.provider.embed_chunks(text, max_tokens, overlap) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("[RAYON] {}", e);
+            return false;
+        }
+    };
+
+    let mut all_embeddings = Vec::new();
+    let mut ranges = Vec::new();
+    for (embedding, (start_byte, end_byte)) in &chunks {
+        all_embeddings.extend(embedding.iter().copied());
+        ranges.push(*start_byte);
+        ranges.push(*end_byte);
+    }
+    let chunk_count = chunks.len();
+
+    let mut boxed_embeddings = all_embeddings.into_boxed_slice();
+    let embeddings_ptr = boxed_embeddings.as_mut_ptr();
+    std::mem::forget(boxed_embeddings);
+
+    let mut boxed_ranges = ranges.into_boxed_slice();
+    let ranges_ptr = boxed_ranges.as_mut_ptr();
+    std::mem::forget(boxed_ranges);
+
+    unsafe {
+        *out_embeddings = embeddings_ptr;
+        *out_ranges = ranges_ptr;
+        *out_count = chunk_count;
+    }
+
+    true
+}
+
 /// Free embeddings result
 #[no_mangle]
 pub extern "C" fn embeddings_free_result(embeddings: *mut f32, len: usize) {
@@ -362,6 +1087,16 @@ pub extern "C" fn embeddings_free_result(embeddings: *mut f32, len: usize) {
     }
 }
 
+/// Free a chunk-range array returned by `embeddings_encode_chunked`
+#[no_mangle]
+pub extern "C" fn embeddings_free_ranges(ranges: *mut usize, len: usize) {
+    if !ranges.is_null() && len > 0 {
+        unsafe {
+            let _ = Vec::from_raw_parts(ranges, len, len);
+        }
+    }
+}
+
 /// Free embeddings handle
 #[no_mangle]
 pub extern "C" fn embeddings_free(handle: *mut EmbeddingsHandle) {
@@ -379,5 +1114,223 @@ pub extern "C" fn embeddings_get_dimension(handle: *const EmbeddingsHandle) -> u
         return 0;
     }
     let handle = unsafe { &*handle };
-    handle.embedding_dim
+    handle.provider.dimension()
+}
+
+// ---------------------------------------------------------------------
+// Top-k cosine similarity search over a stored embedding matrix.
+//
+// Every vector this crate emits is already L2-normalized, so cosine
+// similarity reduces to a plain dot product. This index stores rows in a
+// flat `Vec<f32>` and scores a query against all of them, keeping the top-k
+// with a bounded min-heap. Callers own the id<->document mapping; the index
+// only knows `i64` ids.
+// ---------------------------------------------------------------------
+
+/// Number of rows handed to each rayon task when scanning the index.
+const SEARCH_CHUNK_ROWS: usize = 4096;
+
+pub struct EmbeddingsIndex {
+    dim: usize,
+    ids: Vec<i64>,
+    vectors: Vec<f32>,
+    pool: rayon::ThreadPool,
+}
+
+/// An id/score pair ordered by score (NaN-free f32 scores, via `total_cmp`).
+#[derive(Clone, Copy)]
+struct ScoredId {
+    score: f32,
+    id: i64,
+}
+
+impl PartialEq for ScoredId {
+    fn eq(&self, other: &Self) -> bool {
+        self.score == other.score
+    }
+}
+impl Eq for ScoredId {}
+impl PartialOrd for ScoredId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for ScoredId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.score.total_cmp(&other.score)
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+/// Push a candidate into a bounded top-k min-heap (smallest score at the
+/// top via `Reverse`), evicting the current minimum once the heap is full.
+fn push_top_k(heap: &mut std::collections::BinaryHeap<std::cmp::Reverse<ScoredId>>, k: usize, candidate: ScoredId) {
+    use std::cmp::Reverse;
+    if heap.len() < k {
+        heap.push(Reverse(candidate));
+    } else if let Some(Reverse(min)) = heap.peek() {
+        if candidate.score > min.score {
+            heap.pop();
+            heap.push(Reverse(candidate));
+        }
+    }
+}
+
+/// Create a new empty search index for `dim`-dimensional vectors.
+/// Returns NULL if `dim` is 0 or the thread pool can't be created.
+#[no_mangle]
+pub extern "C" fn embeddings_index_new(dim: usize) -> *mut EmbeddingsIndex {
+    if dim == 0 {
+        return std::ptr::null_mut();
+    }
+
+    let pool = match rayon::ThreadPoolBuilder::new().num_threads(2).build() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("[RAYON] Failed to create index thread pool: {}", e);
+            return std::ptr::null_mut();
+        }
+    };
+
+    Box::into_raw(Box::new(EmbeddingsIndex {
+        dim,
+        ids: Vec::new(),
+        vectors: Vec::new(),
+        pool,
+    }))
+}
+
+/// Append a vector with the given id to the index. `len` must equal the
+/// index's `dim`. Returns false on a null/mismatched input.
+#[no_mangle]
+pub extern "C" fn embeddings_index_add(
+    index: *mut EmbeddingsIndex,
+    id: i64,
+    vec: *const f32,
+    len: usize,
+) -> bool {
+    if index.is_null() || vec.is_null() {
+        return false;
+    }
+    let index = unsafe { &mut *index };
+    if len != index.dim {
+        eprintln!("[RAYON] Index add: expected dim {}, got {}", index.dim, len);
+        return false;
+    }
+    let slice = unsafe { std::slice::from_raw_parts(vec, len) };
+    index.ids.push(id);
+    index.vectors.extend_from_slice(slice);
+    true
+}
+
+/// Number of vectors currently stored in the index.
+#[no_mangle]
+pub extern "C" fn embeddings_index_len(index: *const EmbeddingsIndex) -> usize {
+    if index.is_null() {
+        return 0;
+    }
+    unsafe { &*index }.ids.len()
+}
+
+/// Find the top-k stored vectors by cosine similarity (dot product, since
+/// vectors are L2-normalized) to `query_vec`. Results are written to
+/// `out_ids`/`out_scores` in descending score order; `out_count` may be
+/// less than `k` if the index holds fewer than `k` vectors. The scan is
+/// split into chunks and run across the index's rayon pool.
+#[no_mangle]
+pub extern "C" fn embeddings_index_search(
+    index: *const EmbeddingsIndex,
+    query_vec: *const f32,
+    len: usize,
+    k: usize,
+    out_ids: *mut *mut i64,
+    out_scores: *mut *mut f32,
+    out_count: *mut usize,
+) -> bool {
+    if index.is_null() || query_vec.is_null() || out_ids.is_null()
+        || out_scores.is_null() || out_count.is_null() || k == 0 {
+        return false;
+    }
+
+    let index = unsafe { &*index };
+    if len != index.dim {
+        eprintln!("[RAYON] Index search: expected dim {}, got {}", index.dim, len);
+        return false;
+    }
+    use std::cmp::Reverse;
+
+    let query = unsafe { std::slice::from_raw_parts(query_vec, len) };
+
+    let local_tops: Vec<Vec<ScoredId>> = index.pool.install(|| {
+        index.vectors
+            .par_chunks(index.dim * SEARCH_CHUNK_ROWS)
+            .zip(index.ids.par_chunks(SEARCH_CHUNK_ROWS))
+            .map(|(vec_chunk, id_chunk)| {
+                let mut heap = std::collections::BinaryHeap::new();
+                for (row, &id) in vec_chunk.chunks(index.dim).zip(id_chunk.iter()) {
+                    let score = dot(row, query);
+                    push_top_k(&mut heap, k, ScoredId { score, id });
+                }
+                heap.into_sorted_vec().into_iter().map(|Reverse(s)| s).collect()
+            })
+            .collect()
+    });
+
+    let mut merged = std::collections::BinaryHeap::new();
+    for local in local_tops {
+        for candidate in local {
+            push_top_k(&mut merged, k, candidate);
+        }
+    }
+
+    let results: Vec<ScoredId> = merged.into_sorted_vec().into_iter()
+        .map(|Reverse(s)| s)
+        .collect();
+
+    let mut ids: Vec<i64> = Vec::with_capacity(results.len());
+    let mut scores: Vec<f32> = Vec::with_capacity(results.len());
+    for r in &results {
+        ids.push(r.id);
+        scores.push(r.score);
+    }
+
+    let count = ids.len();
+    let mut boxed_ids = ids.into_boxed_slice();
+    let ids_ptr = boxed_ids.as_mut_ptr();
+    std::mem::forget(boxed_ids);
+
+    let mut boxed_scores = scores.into_boxed_slice();
+    let scores_ptr = boxed_scores.as_mut_ptr();
+    std::mem::forget(boxed_scores);
+
+    unsafe {
+        *out_ids = ids_ptr;
+        *out_scores = scores_ptr;
+        *out_count = count;
+    }
+
+    true
+}
+
+/// Free an id array returned by `embeddings_index_search`
+#[no_mangle]
+pub extern "C" fn embeddings_free_ids(ids: *mut i64, len: usize) {
+    if !ids.is_null() && len > 0 {
+        unsafe {
+            let _ = Vec::from_raw_parts(ids, len, len);
+        }
+    }
+}
+
+/// Free a search index
+#[no_mangle]
+pub extern "C" fn embeddings_index_free(index: *mut EmbeddingsIndex) {
+    if !index.is_null() {
+        unsafe {
+            let _ = Box::from_raw(index);
+        }
+    }
 }